@@ -0,0 +1,219 @@
+use crate::network::{self, Error};
+use crate::primitives::Hash;
+use crate::Client;
+use codec::Decode;
+use jsonrpsee::common::{to_value as to_json_value, Params};
+use quick_cache::sync::Cache;
+use sp_core::storage::{StorageData, StorageKey};
+use sp_version::RuntimeVersion;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+/// Default number of entries retained in each of the block-indexed caches before older ones are
+/// evicted.
+const DEFAULT_CAPACITY: usize = 4096;
+
+/// Tracks which storage keys have been cached for which block, in insertion order, so that once
+/// more than `capacity` distinct blocks have been seen the oldest block's keys can be evicted
+/// alongside it. This keeps the tracker itself bounded, independent of `quick_cache`'s own
+/// per-entry eviction policy on the storage cache it backs.
+struct BlockKeyTracker {
+	capacity: usize,
+	keys_by_block: HashMap<Hash, Vec<StorageKey>>,
+	order: VecDeque<Hash>,
+}
+
+impl BlockKeyTracker {
+	fn new(capacity: usize) -> Self {
+		Self { capacity, keys_by_block: HashMap::new(), order: VecDeque::new() }
+	}
+
+	/// Record that `key` was cached for block `at`. Returns the block evicted to keep the
+	/// tracker within `capacity`, if any, along with the keys that were cached for it.
+	fn track(&mut self, at: Hash, key: StorageKey) -> Option<(Hash, Vec<StorageKey>)> {
+		if !self.keys_by_block.contains_key(&at) {
+			self.order.push_back(at);
+		}
+		let keys = self.keys_by_block.entry(at).or_default();
+		if !keys.contains(&key) {
+			keys.push(key);
+		}
+
+		if self.order.len() <= self.capacity {
+			return None;
+		}
+
+		let oldest = self.order.pop_front().expect("order is non-empty, just checked len");
+		let keys = self.keys_by_block.remove(&oldest).unwrap_or_default();
+		Some((oldest, keys))
+	}
+
+	/// Forget everything tracked for block `at`, returning the keys that were cached for it.
+	fn forget(&mut self, at: Hash) -> Vec<StorageKey> {
+		self.order.retain(|hash| *hash != at);
+		self.keys_by_block.remove(&at).unwrap_or_default()
+	}
+}
+
+/// A [`Client`] wrapper that memoizes storage reads, runtime versions and metadata by the block
+/// hash they were fetched at, so a debugging session that repeatedly queries the same finalized
+/// block doesn't re-issue the same JSON-RPC calls.
+pub struct CachingClient {
+	client: Client,
+	storage: Cache<(Hash, StorageKey), Option<StorageData>>,
+	storage_keys_by_block: Mutex<BlockKeyTracker>,
+	runtime_version: Cache<Hash, RuntimeVersion>,
+	metadata: Cache<Hash, sp_core::Bytes>,
+}
+
+impl CachingClient {
+	/// Wrap `client`, bounding each cache to `capacity` entries (and `storage_keys_by_block` to
+	/// `capacity` distinct blocks).
+	pub fn new(client: Client, capacity: usize) -> Self {
+		Self {
+			client,
+			storage: Cache::new(capacity),
+			storage_keys_by_block: Mutex::new(BlockKeyTracker::new(capacity)),
+			runtime_version: Cache::new(capacity),
+			metadata: Cache::new(capacity),
+		}
+	}
+
+	/// Wrap `client`, bounding each cache to [`DEFAULT_CAPACITY`] entries.
+	pub fn with_default_capacity(client: Client) -> Self {
+		Self::new(client, DEFAULT_CAPACITY)
+	}
+
+	/// The underlying, uncached client.
+	pub fn client(&self) -> &Client {
+		&self.client
+	}
+
+	/// Read and decode a storage item at `key` as of block `at`, consulting the cache first.
+	pub async fn read<T: Decode>(&self, key: StorageKey, at: Hash) -> Result<Option<T>, Error> {
+		let raw = self.read_raw(key, at).await?;
+		raw.map(|data| T::decode(&mut &data.0[..])).transpose().map_err(Error::from)
+	}
+
+	/// Read the raw storage value at `key` as of block `at`, consulting the cache first.
+	pub async fn read_raw(&self, key: StorageKey, at: Hash) -> Result<Option<StorageData>, Error> {
+		if let Some(cached) = self.storage.get(&(at, key.clone())) {
+			return Ok(cached);
+		}
+
+		let at_json = to_json_value(at).expect("Block hash serialization infallible");
+		let key_json = to_json_value(key.clone()).expect("Storage key serialization infallible");
+		let value: Option<StorageData> = self
+			.client
+			.request("state_getStorage", Params::Array(vec![key_json, at_json]))
+			.await?;
+
+		self.storage.insert((at, key.clone()), value.clone());
+
+		let evicted = self
+			.storage_keys_by_block
+			.lock()
+			.expect("storage_keys_by_block lock poisoned")
+			.track(at, key);
+		if let Some((evicted_at, evicted_keys)) = evicted {
+			for evicted_key in evicted_keys {
+				self.storage.remove(&(evicted_at, evicted_key));
+			}
+		}
+
+		Ok(value)
+	}
+
+	/// Get the runtime version at `at`, consulting the cache first.
+	pub async fn get_runtime_version(&self, at: Hash) -> Result<RuntimeVersion, Error> {
+		if let Some(version) = self.runtime_version.get(&at) {
+			return Ok(version);
+		}
+
+		let version = network::get_runtime_version(&self.client, at).await?;
+		self.runtime_version.insert(at, version.clone());
+		Ok(version)
+	}
+
+	/// Get the metadata at `at`, consulting the cache first.
+	pub async fn get_metadata(&self, at: Hash) -> Result<sp_core::Bytes, Error> {
+		if let Some(metadata) = self.metadata.get(&at) {
+			return Ok(metadata);
+		}
+
+		let metadata = network::get_metadata(&self.client, at).await?;
+		self.metadata.insert(at, metadata.clone());
+		Ok(metadata)
+	}
+
+	/// Invalidate every entry cached for block `at`, across all three caches.
+	pub fn clear_at(&self, at: Hash) {
+		self.runtime_version.remove(&at);
+		self.metadata.remove(&at);
+
+		let keys = self
+			.storage_keys_by_block
+			.lock()
+			.expect("storage_keys_by_block lock poisoned")
+			.forget(at);
+		for key in keys {
+			self.storage.remove(&(at, key));
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::BlockKeyTracker;
+	use crate::primitives::Hash;
+	use sp_core::storage::StorageKey;
+
+	fn key(byte: u8) -> StorageKey {
+		StorageKey(vec![byte])
+	}
+
+	#[test]
+	fn tracks_keys_per_block_without_eviction_below_capacity() {
+		let mut tracker = BlockKeyTracker::new(2);
+		assert!(tracker.track(Hash::repeat_byte(1), key(1)).is_none());
+		assert!(tracker.track(Hash::repeat_byte(2), key(2)).is_none());
+	}
+
+	#[test]
+	fn evicts_oldest_block_once_capacity_is_exceeded() {
+		let mut tracker = BlockKeyTracker::new(2);
+		let first = Hash::repeat_byte(1);
+		let second = Hash::repeat_byte(2);
+		let third = Hash::repeat_byte(3);
+
+		tracker.track(first, key(1));
+		tracker.track(second, key(2));
+		let evicted = tracker.track(third, key(3));
+
+		assert_eq!(evicted, Some((first, vec![key(1)])));
+	}
+
+	#[test]
+	fn forget_removes_a_block_without_waiting_for_eviction() {
+		let mut tracker = BlockKeyTracker::new(2);
+		let at = Hash::repeat_byte(1);
+		tracker.track(at, key(1));
+		tracker.track(at, key(2));
+
+		assert_eq!(tracker.forget(at), vec![key(1), key(2)]);
+		assert_eq!(tracker.forget(at), Vec::<StorageKey>::new());
+	}
+
+	#[test]
+	fn track_does_not_duplicate_a_key_re_read_after_quick_cache_evicted_it() {
+		let mut tracker = BlockKeyTracker::new(2);
+		let at = Hash::repeat_byte(1);
+
+		tracker.track(at, key(1));
+		// quick_cache may have already dropped the (at, key(1)) entry on its own eviction policy;
+		// re-reading it re-inserts into quick_cache but must not grow keys_by_block[at] again.
+		tracker.track(at, key(1));
+
+		assert_eq!(tracker.forget(at), vec![key(1)]);
+	}
+}
@@ -0,0 +1,76 @@
+use crate::network::{self, CurrencyToVoteHandler, Error};
+use crate::primitives::{runtime, Hash};
+use crate::Client;
+use frame_system::EventRecord;
+use futures::stream::{self, Stream};
+use jsonrpsee::common::Params;
+use sp_runtime::traits::Header as _;
+use std::pin::Pin;
+use tokio::sync::oneshot;
+
+/// A finalized head, paired with the events emitted in that block and a [`CurrencyToVoteHandler`]
+/// snapshotted at it.
+pub type HeadEvent = (Hash, Vec<EventRecord<runtime::Event, Hash>>, CurrencyToVoteHandler);
+
+/// Stops a running [`Follower`] subscription, either explicitly via [`ShutdownHandle::shutdown`]
+/// or implicitly when dropped.
+pub struct ShutdownHandle {
+	tx: oneshot::Sender<()>,
+}
+
+impl ShutdownHandle {
+	/// Stop the subscription this handle was returned alongside.
+	pub fn shutdown(self) {
+		let _ = self.tx.send(());
+	}
+}
+
+/// Follows finalized heads as they arrive, automatically snapshotting a [`CurrencyToVoteHandler`]
+/// and yielding the new head's events, instead of requiring callers to poll [`network::get_head`]
+/// themselves.
+pub struct Follower;
+
+impl Follower {
+	/// Subscribe to `chain_subscribeFinalizedHeads` on `client`, returning a stream of
+	/// `(head, events, vote handler)` results and a handle to stop the subscription.
+	///
+	/// Each item is `Err` if snapshotting the issuance for that particular head failed (e.g. a
+	/// transient RPC hiccup); the follower keeps running and yields the next finalized head
+	/// regardless, rather than treating the error as terminal. A head whose `System::Events`
+	/// storage yields no value is reported with an empty event list, not as an `Err`.
+	pub async fn start(
+		client: Client,
+	) -> Result<(Pin<Box<dyn Stream<Item = Result<HeadEvent, Error>> + Send>>, ShutdownHandle), Error> {
+		let subscription = client
+			.subscribe::<runtime::Header>(
+				"chain_subscribeFinalizedHeads",
+				Params::None,
+				"chain_unsubscribeFinalizedHeads",
+			)
+			.await?;
+
+		let (tx, rx) = oneshot::channel();
+
+		let stream = stream::unfold(
+			(client, subscription, rx),
+			|(client, mut subscription, mut shutdown)| async move {
+				loop {
+					tokio::select! {
+						_ = &mut shutdown => return None,
+						header = subscription.next() => {
+							let at = header.hash();
+
+							let to_vote = CurrencyToVoteHandler::at(&client, at).await;
+							let events = network::get_events_at(&client, at).await.unwrap_or_default();
+
+							let item = to_vote.map(|handler| (at, events, handler));
+							return Some((item, (client, subscription, shutdown)));
+						}
+					}
+				}
+			},
+		);
+
+		Ok((Box::pin(stream), ShutdownHandle { tx }))
+	}
+}
@@ -5,45 +5,120 @@ use codec::Decode;
 use frame_support::{Blake2_128Concat, Twox64Concat};
 use frame_system::AccountInfo;
 use jsonrpsee::common::{to_value as to_json_value, Params};
-use pallet_balances::AccountData;
+use pallet_balances::{AccountData, BalanceLock};
 use pallet_transaction_payment_rpc_runtime_api::RuntimeDispatchInfo;
 use sp_core::storage::{StorageData, StorageKey};
 use sp_runtime::traits::Convert;
 
 static ISSUANCE: RefCell<Balance> = RefCell::new(0);
 
-/// Deals with total issuance
+/// Errors that can occur while talking to the node or decoding its responses.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+	/// The underlying JSON-RPC request failed.
+	#[error("rpc request failed: {0}")]
+	Rpc(#[from] jsonrpsee::client::RequestError),
+	/// SCALE-decoding a value returned by the node failed.
+	#[error("failed to decode value: {0}")]
+	Decode(#[from] codec::Error),
+	/// The node did not return a block for the requested hash.
+	#[error("no block found at {0}")]
+	BlockNotFound(Hash),
+	/// The node did not return a value for the requested storage key.
+	#[error("no storage item found at key {0:?}")]
+	StorageNotFound(StorageKey),
+	/// `chain_getFinalizedHead` returned no head at all.
+	#[error("node did not return a finalized head")]
+	MissingHead,
+	/// A call that is always expected to return a value returned `None`.
+	#[error("rpc call unexpectedly returned no result")]
+	MissingResult,
+	/// A stored identity's display name was not valid utf-8.
+	#[error("identity display name is not valid utf-8")]
+	InvalidUtf8Identity,
+}
+
+/// Deals with total issuance.
+///
+/// Deprecated: prefer constructing a [`CurrencyToVoteHandler`] with the total issuance read at a
+/// specific block, rather than stashing it in a process-wide global.
 pub mod issuance {
-	use super::{get_total_issuance, ISSUANCE};
+	use super::{get_total_issuance, Error, ISSUANCE};
 	use crate::{Balance, Client, Hash};
 
 	/// Get the previously set total issuance.
+	#[deprecated(note = "carry a CurrencyToVoteHandler instance instead of a global issuance value")]
 	pub fn get() -> Balance {
 		ISSUANCE.borrow().clone()
 	}
 
-	/// Set the total issuance. Any code wanting to use `CurrencyToVoteHandler` must call this first
-	/// to set correct value in the global pointer.
-	pub async fn set(client: &Client, at: Hash) {
-		let total_issuance = get_total_issuance(client, at).await;
+	/// Set the total issuance. Any code wanting to use [`GlobalCurrencyToVoteHandler`] must call
+	/// this first to set correct value in the global pointer.
+	#[deprecated(note = "carry a CurrencyToVoteHandler instance instead of a global issuance value")]
+	pub async fn set(client: &Client, at: Hash) -> Result<(), Error> {
+		let total_issuance = get_total_issuance(client, at).await?;
 		*ISSUANCE.borrow_mut() = total_issuance;
+		Ok(())
 	}
 }
 
-pub struct CurrencyToVoteHandler;
+/// Converts between a balance and the `u64` vote weight used by the staking election, scaled by a
+/// snapshot of the chain's total issuance.
+///
+/// Unlike the old global-state handler, the issuance snapshot is carried on the instance, so two
+/// concurrent analyses against different chains or blocks no longer clobber each other.
+pub struct CurrencyToVoteHandler {
+	total_issuance: Balance,
+}
+
 impl CurrencyToVoteHandler {
+	/// Build a handler from an already-known total issuance.
+	pub fn new(total_issuance: Balance) -> Self {
+		Self { total_issuance }
+	}
+
+	/// Build a handler from the total issuance at block `at`.
+	pub async fn at(client: &Client, at: Hash) -> Result<Self, Error> {
+		get_total_issuance(client, at).await.map(Self::new)
+	}
+
+	fn factor(&self) -> u128 {
+		(self.total_issuance / u64::max_value() as u128).max(1)
+	}
+
+	/// Convert a balance into the `u64` vote weight used by elections.
+	pub fn to_vote(&self, x: Balance) -> u64 {
+		(x / self.factor()) as u64
+	}
+
+	/// Convert a `u64` vote weight back into a balance.
+	pub fn to_balance(&self, x: u64) -> Balance {
+		x as Balance * self.factor()
+	}
+}
+
+/// Deprecated global-state equivalent of [`CurrencyToVoteHandler`], kept for callers that haven't
+/// migrated yet. Requires [`issuance::set`] to be called first; silently uses a factor of 1
+/// otherwise.
+#[deprecated(note = "use CurrencyToVoteHandler::new or CurrencyToVoteHandler::at instead")]
+pub struct GlobalCurrencyToVoteHandler;
+
+#[allow(deprecated)]
+impl GlobalCurrencyToVoteHandler {
 	fn factor() -> u128 {
 		(issuance::get() / u64::max_value() as u128).max(1)
 	}
 }
 
-impl Convert<u128, u64> for CurrencyToVoteHandler {
+#[allow(deprecated)]
+impl Convert<u128, u64> for GlobalCurrencyToVoteHandler {
 	fn convert(x: Balance) -> u64 {
 		(x / Self::factor()) as u64
 	}
 }
 
-impl Convert<u128, u128> for CurrencyToVoteHandler {
+#[allow(deprecated)]
+impl Convert<u128, u128> for GlobalCurrencyToVoteHandler {
 	fn convert(x: u128) -> Balance {
 		x * Self::factor()
 	}
@@ -53,7 +128,7 @@ impl Convert<u128, u128> for CurrencyToVoteHandler {
 ///
 /// seemingly DEPRECATED.
 #[allow(dead_code)]
-pub async fn get_nick(who: &AccountId, client: &Client, at: Hash) -> String {
+pub async fn get_nick(who: &AccountId, client: &Client, at: Hash) -> Result<String, Error> {
 	let nick = storage::read::<(Vec<u8>, Balance)>(
 		storage::map_key::<Twox64Concat>(b"Nicks", b"NameOf", who.as_ref()),
 		client,
@@ -61,14 +136,13 @@ pub async fn get_nick(who: &AccountId, client: &Client, at: Hash) -> String {
 	)
 	.await;
 
-	if nick.is_some() {
-		String::from_utf8(nick.unwrap().0).unwrap()
-	} else {
-		String::from("[NO_NICK]")
-	}
+	Ok(match nick {
+		Some((raw, _)) => String::from_utf8(raw).map_err(|_| Error::InvalidUtf8Identity)?,
+		None => String::from("[NO_NICK]"),
+	})
 }
 
-pub async fn get_identity(who: &AccountId, client: &Client, at: Hash) -> String {
+pub async fn get_identity(who: &AccountId, client: &Client, at: Hash) -> Result<String, Error> {
 	use pallet_identity::{Data, Registration};
 	let maybe_identity = storage::read::<Registration<Balance>>(
 		storage::map_key::<Twox64Concat>(b"Identity", b"IdentityOf", who.as_ref()),
@@ -81,55 +155,49 @@ pub async fn get_identity(who: &AccountId, client: &Client, at: Hash) -> String
 		let info = identity.info;
 		let display = info.display;
 
-		match display {
-			Data::Raw(bytes) => String::from_utf8(bytes).expect("Identity not utf-8"),
+		Ok(match display {
+			Data::Raw(bytes) => String::from_utf8(bytes).map_err(|_| Error::InvalidUtf8Identity)?,
 			_ => "OPAQUE_IDENTITY".to_string(),
-		}
+		})
 	} else {
-		"NO_IDENT".to_string()
+		Ok("NO_IDENT".to_string())
 	}
 }
 
 /// Get the latest finalized head of the chain.
-pub async fn get_head(client: &Client) -> Hash {
-	let data: Option<StorageData> = client
-		.request("chain_getFinalizedHead", Params::None)
-		.await
-		.expect("get chain finalized head request failed");
-	let now_raw = data.expect("Should always get the head hash").0;
-	<Hash as Decode>::decode(&mut &*now_raw).expect("Block hash should decode")
+pub async fn get_head(client: &Client) -> Result<Hash, Error> {
+	let data: Option<StorageData> = client.request("chain_getFinalizedHead", Params::None).await?;
+	let now_raw = data.ok_or(Error::MissingHead)?.0;
+	Ok(<Hash as Decode>::decode(&mut &*now_raw)?)
 }
 
 /// Get the block at a particular hash
-pub async fn get_block(client: &Client, at: Hash) -> runtime::SignedBlock {
-	let at = to_json_value(at).expect("Block hash serialization infallible");
+pub async fn get_block(client: &Client, at: Hash) -> Result<runtime::SignedBlock, Error> {
+	let at_json = to_json_value(at).expect("Block hash serialization infallible");
 	let data: Option<runtime::SignedBlock> = client
-		.request("chain_getBlock", Params::Array(vec![at]))
-		.await
-		.expect("Failed to decode block");
+		.request("chain_getBlock", Params::Array(vec![at_json]))
+		.await?;
 
-	data.unwrap()
+	data.ok_or(Error::BlockNotFound(at))
 }
 
 /// Get the runtime version at the given block.
-pub async fn get_runtime_version(client: &Client, at: Hash) -> sp_version::RuntimeVersion {
-	let at = to_json_value(at).expect("Block hash serialization infallible");
+pub async fn get_runtime_version(client: &Client, at: Hash) -> Result<sp_version::RuntimeVersion, Error> {
+	let at_json = to_json_value(at).expect("Block hash serialization infallible");
 	let data: Option<sp_version::RuntimeVersion> = client
-		.request("state_getRuntimeVersion", Params::Array(vec![at]))
-		.await
-		.expect("Failed to decode block");
+		.request("state_getRuntimeVersion", Params::Array(vec![at_json]))
+		.await?;
 
-	data.unwrap()
+	data.ok_or(Error::BlockNotFound(at))
 }
 
-pub async fn get_metadata(client: &Client, at: Hash) -> sp_core::Bytes {
-	let at = to_json_value(at).expect("Block hash serialization infallible");
+pub async fn get_metadata(client: &Client, at: Hash) -> Result<sp_core::Bytes, Error> {
+	let at_json = to_json_value(at).expect("Block hash serialization infallible");
 	let data: Option<sp_core::Bytes> = client
-		.request("state_getMetadata", Params::Array(vec![at]))
-		.await
-		.expect("Failed to decode block");
+		.request("state_getMetadata", Params::Array(vec![at_json]))
+		.await?;
 
-	data.unwrap()
+	data.ok_or(Error::BlockNotFound(at))
 }
 
 /// Get the extrinsic info
@@ -137,24 +205,22 @@ pub async fn query_info(
 	extrinsic: sp_core::Bytes,
 	client: &Client,
 	at: Hash,
-) -> RuntimeDispatchInfo<Balance> {
-	let at = to_json_value(at).expect("Block hash serialization infallible");
+) -> Result<RuntimeDispatchInfo<Balance>, Error> {
+	let at_json = to_json_value(at).expect("Block hash serialization infallible");
 	let extrinsic = to_json_value(extrinsic).expect("extrinsic serialization infallible");
 	let data: Option<RuntimeDispatchInfo<Balance>> = client
-		.request("payment_queryInfo", Params::Array(vec![extrinsic, at]))
-		.await
-		.unwrap();
+		.request("payment_queryInfo", Params::Array(vec![extrinsic, at_json]))
+		.await?;
 
-	data.unwrap()
+	data.ok_or(Error::MissingResult)
 }
 
-pub async fn got_storage_size(key: StorageKey, client: &Client, at: Hash) -> Option<u64> {
+pub async fn got_storage_size(key: StorageKey, client: &Client, at: Hash) -> Result<Option<u64>, Error> {
 	let at = to_json_value(at).expect("Block hash serialization infallible");
 	let key = to_json_value(key).expect("extrinsic serialization infallible");
-	client
+	Ok(client
 		.request("state_getStorageSize", Params::Array(vec![key, at]))
-		.await
-		.unwrap()
+		.await?)
 }
 
 pub async fn get_events_at(
@@ -169,18 +235,17 @@ pub async fn get_account_data_at(
 	account: &[u8],
 	client: &Client,
 	at: Hash,
-) -> AccountInfo<Nonce, AccountData<Balance>> {
-	storage::read::<AccountInfo<Nonce, AccountData<Balance>>>(
-		storage::map_key::<Blake2_128Concat>(b"System", b"Account", account),
-		client,
-		at,
-	)
-	.await
-	.unwrap()
+) -> Result<AccountInfo<Nonce, AccountData<Balance>>, Error> {
+	// An account that has never been touched on-chain simply has no `System::Account` entry;
+	// that's not an error condition, it's a fresh account with zero balance/nonce.
+	let key = storage::map_key::<Blake2_128Concat>(b"System", b"Account", account);
+	Ok(storage::read::<AccountInfo<Nonce, AccountData<Balance>>>(key, client, at)
+		.await
+		.unwrap_or_default())
 }
 
 /// Get total issuance of the chain.
-async fn get_total_issuance(client: &Client, at: Hash) -> Balance {
+async fn get_total_issuance(client: &Client, at: Hash) -> Result<Balance, Error> {
 	let maybe_total_issuance = storage::read::<Balance>(
 		storage::value_key(b"Balances", b"TotalIssuance"),
 		&client,
@@ -188,5 +253,110 @@ async fn get_total_issuance(client: &Client, at: Hash) -> Balance {
 	)
 	.await;
 
-	maybe_total_issuance.unwrap_or(0)
+	Ok(maybe_total_issuance.unwrap_or(0))
+}
+
+/// Get the free balance of `account` at block `at`.
+pub async fn free_balance(account: &[u8], client: &Client, at: Hash) -> Result<Balance, Error> {
+	Ok(get_account_data_at(account, client, at).await?.data.free)
+}
+
+/// Get the reserved balance of `account` at block `at`.
+pub async fn reserved_balance(account: &[u8], client: &Client, at: Hash) -> Result<Balance, Error> {
+	Ok(get_account_data_at(account, client, at).await?.data.reserved)
+}
+
+/// Get the nonce of `account` at block `at`.
+pub async fn nonce(account: &[u8], client: &Client, at: Hash) -> Result<Nonce, Error> {
+	Ok(get_account_data_at(account, client, at).await?.nonce)
+}
+
+/// Get the balance locks placed on `account` at block `at`.
+pub async fn locks(account: &[u8], client: &Client, at: Hash) -> Result<Vec<BalanceLock<Balance>>, Error> {
+	let key = storage::map_key::<Blake2_128Concat>(b"Balances", b"Locks", account);
+	Ok(storage::read(key, client, at).await.unwrap_or_default())
+}
+
+/// A convenience snapshot of an account's on-chain state at a given block, so callers don't have
+/// to hand-assemble `map_key::<Blake2_128Concat>` calls for each field they need.
+pub struct AccountOverview {
+	pub free: Balance,
+	pub reserved: Balance,
+	pub nonce: Nonce,
+	pub locks: Vec<BalanceLock<Balance>>,
+	pub identity: String,
+	pub vote_weight: u64,
+}
+
+impl AccountOverview {
+	/// Assemble an overview from its already-fetched parts. Kept separate from
+	/// [`account_overview`] so the (otherwise purely arithmetic) vote weight computation is
+	/// testable without a live node.
+	fn assemble(
+		free: Balance,
+		reserved: Balance,
+		nonce: Nonce,
+		locks: Vec<BalanceLock<Balance>>,
+		identity: String,
+		to_vote: &CurrencyToVoteHandler,
+	) -> Self {
+		Self { free, reserved, nonce, vote_weight: to_vote.to_vote(free), locks, identity }
+	}
+}
+
+/// Assemble an [`AccountOverview`] for `account` at block `at`, covering balances, nonce, identity
+/// and its computed vote weight in one call.
+pub async fn account_overview(account: &AccountId, client: &Client, at: Hash) -> Result<AccountOverview, Error> {
+	let account_data = get_account_data_at(account.as_ref(), client, at).await?;
+	let identity = get_identity(account, client, at).await?;
+	let locks = locks(account.as_ref(), client, at).await?;
+	let to_vote = CurrencyToVoteHandler::at(client, at).await?;
+
+	Ok(AccountOverview::assemble(
+		account_data.data.free,
+		account_data.data.reserved,
+		account_data.nonce,
+		locks,
+		identity,
+		&to_vote,
+	))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{AccountOverview, CurrencyToVoteHandler};
+
+	#[test]
+	fn factor_is_at_least_one_below_u64_max_issuance() {
+		let handler = CurrencyToVoteHandler::new(1_000);
+		assert_eq!(handler.to_vote(1_000), 1_000);
+	}
+
+	#[test]
+	fn to_vote_scales_down_by_the_issuance_factor() {
+		let total_issuance = 10 * u64::max_value() as u128;
+		let handler = CurrencyToVoteHandler::new(total_issuance);
+		// factor == 10, so 30 units of balance convert to 3 votes.
+		assert_eq!(handler.to_vote(30), 3);
+	}
+
+	#[test]
+	fn to_balance_is_the_inverse_of_to_vote() {
+		let total_issuance = 10 * u64::max_value() as u128;
+		let handler = CurrencyToVoteHandler::new(total_issuance);
+		assert_eq!(handler.to_balance(handler.to_vote(30)), 30);
+	}
+
+	#[test]
+	fn account_overview_assemble_computes_vote_weight_from_free_balance() {
+		let to_vote = CurrencyToVoteHandler::new(10 * u64::max_value() as u128);
+		let overview = AccountOverview::assemble(30, 5, 1, Vec::new(), "NO_IDENT".to_string(), &to_vote);
+
+		assert_eq!(overview.free, 30);
+		assert_eq!(overview.reserved, 5);
+		assert_eq!(overview.nonce, 1);
+		assert_eq!(overview.vote_weight, 3);
+		assert!(overview.locks.is_empty());
+		assert_eq!(overview.identity, "NO_IDENT");
+	}
 }